@@ -0,0 +1,248 @@
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::Notify;
+
+/// Creates a wait-group over `value`: any number of [`Ref`] handles may be cloned and dropped
+/// freely, and the returned [`Waiter`] resolves exactly once, after the *last* `Ref` is dropped -
+/// handing back the original `value`.
+///
+/// This replaces the old pattern of threading an `Arc<T>` plus a `Notify` through callers and
+/// having the waiter special-case `Arc::strong_count() == 2` to account for its own handle. Here
+/// the `Waiter` holds no `Ref` of its own, so there's no magic constant and no upper bound on how
+/// many `Ref`s may exist.
+pub fn await_drop<T>(value: T) -> (Ref<T>, Waiter<T>) {
+    let inner = Arc::new(value);
+    let count = Arc::new(AtomicUsize::new(1));
+    let notify = Arc::new(Notify::new());
+    let r = Ref {
+        inner: Some(inner.clone()),
+        count: count.clone(),
+        notify: notify.clone(),
+    };
+    let waiter = Waiter {
+        inner,
+        count,
+        notify,
+    };
+    (r, waiter)
+}
+
+/// A cloneable handle produced by [`await_drop`]. Derefs to the wrapped value. Dropping the last
+/// outstanding `Ref` wakes the paired [`Waiter`].
+pub struct Ref<T> {
+    // `Option` so `Drop` can release this handle's `Arc<T>` clone *before* decrementing `count`
+    // and notifying - otherwise a `Waiter` woken on another thread could observe `count == 0` and
+    // call `Arc::try_unwrap` while this field's drop (which runs after the `Drop` impl's body
+    // returns) hasn't happened yet, spuriously failing the unwrap.
+    inner: Option<Arc<T>>,
+    count: Arc<AtomicUsize>,
+    notify: Arc<Notify>,
+}
+
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        Self {
+            inner: self.inner.clone(),
+            count: self.count.clone(),
+            notify: self.notify.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Ref<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref().expect("only taken in Drop::drop")
+    }
+}
+
+impl<T> Drop for Ref<T> {
+    fn drop(&mut self) {
+        // Drop this handle's `Arc<T>` clone before touching `count`/`notify`, so that by the time
+        // a waiting thread observes `count == 0` this clone is already gone and `Arc::try_unwrap`
+        // on the `Waiter` side can't race against it.
+        drop(self.inner.take());
+        if self.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// The single waiting half produced by [`await_drop`]. Call [`Waiter::wait`] to await every
+/// [`Ref`] being dropped and reclaim the wrapped value.
+pub struct Waiter<T> {
+    inner: Arc<T>,
+    count: Arc<AtomicUsize>,
+    notify: Arc<Notify>,
+}
+
+impl<T> Waiter<T> {
+    /// Resolves once every [`Ref`] handed out alongside this `Waiter` has been dropped, then
+    /// hands back the wrapped value.
+    pub async fn wait(self) -> T {
+        while self.count.load(Ordering::Acquire) > 0 {
+            self.notify.notified().await;
+        }
+        match Arc::try_unwrap(self.inner) {
+            Ok(v) => v,
+            Err(_) => unreachable!("all `Ref`s are dropped once count reaches 0"),
+        }
+    }
+
+    /// Like [`Self::wait`], but gives up once `deadline` elapses, returning `Err(self)` so the
+    /// caller can inspect [`Self::outstanding`] and retry or force a decision.
+    pub async fn wait_with_deadline(self, deadline: tokio::time::Instant) -> Result<T, Self> {
+        loop {
+            if self.count.load(Ordering::Acquire) == 0 {
+                // No `Ref` exists once `count` reaches 0, so `self.inner` is the sole `Arc`
+                // holder and this can never fail.
+                return Ok(Arc::try_unwrap(self.inner)
+                    .unwrap_or_else(|_| unreachable!("all `Ref`s are dropped once count reaches 0")));
+            }
+            match tokio::time::timeout_at(deadline, self.notify.notified()).await {
+                Ok(()) => continue,
+                Err(_) => return Err(self),
+            }
+        }
+    }
+
+    /// The number of [`Ref`] handles still outstanding.
+    pub fn outstanding(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Access to the wrapped value while still waiting, without consuming the waiter.
+    pub fn peek(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> Ref<T> {
+    /// The number of outstanding `Ref` handles sharing this wait-group, including this one.
+    pub fn ref_count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn fires_exactly_once_after_last_ref_drop() {
+        let (r, waiter) = await_drop(42u32);
+        let refs: Vec<_> = (0..8).map(|_| r.clone()).collect();
+        drop(r);
+
+        let handles: Vec<_> = refs
+            .into_iter()
+            .map(|r| {
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    drop(r);
+                })
+            })
+            .collect();
+
+        let value = waiter.wait().await;
+        assert_eq!(value, 42);
+
+        for h in handles {
+            h.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_immediately_with_no_outstanding_refs() {
+        let (r, waiter) = await_drop("hello".to_string());
+        drop(r);
+        assert_eq!(waiter.wait().await, "hello");
+    }
+
+    #[tokio::test]
+    async fn wait_with_deadline_times_out_while_refs_outstanding() {
+        let (r, waiter) = await_drop(7u32);
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(10);
+        let waiter = match waiter.wait_with_deadline(deadline).await {
+            Err(w) => w,
+            Ok(_) => panic!("should not have resolved while a Ref is outstanding"),
+        };
+        assert_eq!(waiter.outstanding(), 1);
+        drop(r);
+        assert_eq!(waiter.wait().await, 7);
+    }
+
+    /// Regression test for a race where `Waiter::wait`/`wait_with_deadline` could observe
+    /// `count == 0` and call `Arc::try_unwrap` before the last `Ref`'s own `Arc<T>` clone had
+    /// actually been released, spuriously hitting the `unreachable!()`. Uses a `Barrier` to line
+    /// up the drop and the wait on separate OS threads with no slack between them - a
+    /// sleep-based test can't reliably hit this window.
+    #[test]
+    fn wait_does_not_race_last_ref_drop() {
+        use std::sync::Barrier;
+
+        for _ in 0..2000 {
+            let (r, waiter) = await_drop(0u32);
+            let barrier = Arc::new(Barrier::new(2));
+
+            let dropper = {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    drop(r);
+                })
+            };
+            let waiter_thread = std::thread::spawn(move || {
+                barrier.wait();
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .unwrap()
+                    .block_on(waiter.wait());
+            });
+
+            dropper.join().unwrap();
+            waiter_thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn wait_with_deadline_does_not_race_last_ref_drop() {
+        use std::sync::Barrier;
+
+        for _ in 0..2000 {
+            let (r, waiter) = await_drop(0u32);
+            let barrier = Arc::new(Barrier::new(2));
+
+            let dropper = {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    drop(r);
+                })
+            };
+            let waiter_thread = std::thread::spawn(move || {
+                barrier.wait();
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .unwrap()
+                    .block_on(async {
+                        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+                        assert!(waiter.wait_with_deadline(deadline).await.is_ok());
+                    });
+            });
+
+            dropper.join().unwrap();
+            waiter_thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,78 @@
+mod dispatcher;
+
+pub use dispatcher::*;
+
+use crate::{pollers::GatewayRef, WorkerConfig};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Polls and executes workflow/activity tasks for a single task queue.
+pub struct Worker {
+    config: WorkerConfig,
+    sticky_queue: Option<String>,
+    gateway: Arc<GatewayRef>,
+    shutdown_requested: AtomicBool,
+    outstanding_workflow_tasks: AtomicUsize,
+    outstanding_activity_tasks: AtomicUsize,
+    inflight_polls: AtomicUsize,
+}
+
+impl Worker {
+    pub(crate) fn new(
+        config: WorkerConfig,
+        sticky_queue: Option<String>,
+        gateway: Arc<GatewayRef>,
+    ) -> Self {
+        Self {
+            config,
+            sticky_queue,
+            gateway,
+            shutdown_requested: AtomicBool::new(false),
+            outstanding_workflow_tasks: AtomicUsize::new(0),
+            outstanding_activity_tasks: AtomicUsize::new(0),
+            inflight_polls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Requests that this worker stop polling for new work and begin draining outstanding tasks.
+    pub(crate) async fn shutdown(&self) {
+        self.shutdown_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Tears down this worker's resources. Consumes `self`, since by the time this is called no
+    /// other handles to the worker remain.
+    pub(crate) async fn finalize_shutdown(self) {}
+
+    /// Like [`Self::finalize_shutdown`], but takes `&self` instead of consuming the worker. Used
+    /// when a drain deadline elapsed with a handle still outstanding, so the caller only has a
+    /// shared reference rather than ownership. Best-effort: may run concurrently with whatever
+    /// still holds a reference.
+    pub(crate) async fn force_finalize_shutdown(&self) {}
+
+    /// Number of workflow tasks this worker is currently processing.
+    pub(crate) fn outstanding_workflow_tasks(&self) -> usize {
+        self.outstanding_workflow_tasks.load(Ordering::Relaxed)
+    }
+
+    /// Number of activity tasks this worker is currently processing.
+    pub(crate) fn outstanding_activity_tasks(&self) -> usize {
+        self.outstanding_activity_tasks.load(Ordering::Relaxed)
+    }
+
+    /// Number of polls currently in flight against the server.
+    pub(crate) fn inflight_poll_count(&self) -> usize {
+        self.inflight_polls.load(Ordering::Relaxed)
+    }
+
+    /// This worker's sticky queue name, if it has one.
+    pub(crate) fn sticky_queue_name(&self) -> Option<String> {
+        self.sticky_queue.clone()
+    }
+
+    /// Whether [`Self::shutdown`] has been called on this worker.
+    pub(crate) fn shutdown_requested(&self) -> bool {
+        self.shutdown_requested.load(Ordering::SeqCst)
+    }
+}